@@ -14,48 +14,146 @@
 
 //! # Client support
 //!
-//! Support for connecting to JSONRPC servers over HTTP, sending requests,
-//! and parsing responses
+//! Support for connecting to JSONRPC servers, sending requests, and parsing
+//! responses
 //!
 
-use std::io;
-use std::io::Read;
+mod ipc;
+mod transport;
+mod ws;
+
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use hyper;
-use hyper::client::Client as HyperClient;
-use hyper::header::{Authorization, Bearer, ContentType, Headers};
 use serde;
 use serde_json;
 
 use super::{Request, Response};
 use error::Error;
 
-/// A handle to a remote JSONRPC server
-pub struct Client {
-    url: String,
-    token: Option<String>,
-    client: HyperClient,
+pub use self::ipc::IpcTransport;
+pub use self::transport::{HttpTransport, Transport};
+pub use self::ws::WsTransport;
+
+/// Controls how `Client` retries a request after a retryable failure
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first; 1 disables retrying
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Factor the delay is multiplied by after each failed attempt
+    pub multiplier: u32,
+    /// RPC error codes (as returned in `Response.error.code`) that should
+    /// also trigger a retry of the whole request, in addition to retryable
+    /// transport failures
+    pub retryable_rpc_codes: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            // Like the single resend this type replaced, retries once with
+            // no configuration required, now after a 100ms delay and on any
+            // error `Error::is_retryable` accepts (broken pipe, aborted or
+            // reset connection, timeout).
+            max_attempts: 2,
+            base_delay: Duration::from_millis(100),
+            multiplier: 2,
+            retryable_rpc_codes: vec![],
+        }
+    }
+}
+
+/// The body of a batch reply as it appears on the wire. Per the JSON-RPC
+/// 2.0 spec a server replies to a batch with a JSON array, but some servers
+/// collapse a batch of one down to a bare object, so both shapes are
+/// accepted here.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchResponse {
+    Batch(Vec<Response>),
+    Single(Response),
+}
+
+/// Compares a response `id` to the request `id` it should match. In strict
+/// mode this is exact equality per the spec; in lenient mode, servers that
+/// reply with (say) a string `id` for a request sent with a numeric `id`
+/// are still considered a match, by comparing their canonical string forms.
+fn ids_match(response_id: &serde_json::Value, request_id: &serde_json::Value, strict: bool) -> bool {
+    response_id == request_id || (!strict && canonical_id(response_id) == canonical_id(request_id))
+}
+
+/// A JSON scalar id's value, ignoring whether it was encoded as a string or
+/// a number
+fn canonical_id(id: &serde_json::Value) -> String {
+    match *id {
+        serde_json::Value::String(ref s) => s.clone(),
+        ref other => other.to_string(),
+    }
+}
+
+/// A handle to a remote JSONRPC server, generic over how requests actually
+/// reach it. Defaults to `HttpTransport`, which is what `Client::new` and
+/// `Client::with_basic_auth` build for callers who don't need anything else.
+pub struct Client<T: Transport = HttpTransport> {
+    transport: T,
     nonce: Arc<Mutex<u64>>,
+    retry_policy: RetryPolicy,
+    strict: bool,
+}
+
+impl Client<HttpTransport> {
+    /// Creates a new client that talks to `url` over HTTP
+    pub fn new(url: String, token: Option<String>) -> Client<HttpTransport> {
+        Client::from_transport(HttpTransport::new(url, token))
+    }
+
+    /// Creates a new client that authenticates with HTTP Basic credentials
+    /// instead of a Bearer token, as required by Bitcoin Core and other
+    /// node daemons
+    pub fn with_basic_auth(url: String, username: String, password: Option<String>) -> Client<HttpTransport> {
+        Client::from_transport(HttpTransport::with_basic_auth(url, username, password))
+    }
 }
 
-impl Client {
-    /// Creates a new client
-    pub fn new(url: String, token: Option<String>) -> Client {
+impl<T: Transport> Client<T> {
+    /// Creates a new client on top of an already-constructed transport, for
+    /// callers using something other than plain HTTP (see `WsTransport`)
+    pub fn from_transport(transport: T) -> Client<T> {
         Client {
-            url,
-            token,
-            client: HyperClient::new(),
+            transport,
             nonce: Arc::new(Mutex::new(0)),
+            retry_policy: RetryPolicy::default(),
+            strict: true,
         }
     }
 
+    /// Sets the policy used to retry requests that fail with a retryable
+    /// transport error or RPC error code
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Client<T> {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Disables strict 2.0-spec checking (on by default) to interoperate
+    /// with servers that reply with an `id` of a different JSON type than
+    /// the one that was sent (e.g. the string `"1"` for a request sent with
+    /// the number `1`), by comparing `id`s with numeric/string coercion
+    /// instead of exact equality. A missing `jsonrpc` field is, and always
+    /// was, tolerated regardless of this setting.
+    pub fn with_strict(mut self, strict: bool) -> Client<T> {
+        self.strict = strict;
+        self
+    }
+
     /// Make a request and deserialize the response
-    pub fn do_rpc<T: for<'a> serde::de::Deserialize<'a>>(
+    pub fn do_rpc<U: for<'a> serde::de::Deserialize<'a>>(
         &self,
         rpc_name: &str,
         args: serde_json::value::Value,
-    ) -> Result<T, Error> {
+    ) -> Result<U, Error> {
         let request = self.build_request(rpc_name, args);
         let response = self.send_request(&request)?;
 
@@ -63,63 +161,130 @@ impl Client {
     }
 
     /// Sends a request to a client
+    ///
+    /// The request keeps its `id` fixed across every attempt, so retries of
+    /// the same logical request still match the eventual response by `id`.
     pub fn send_request(&self, request: &Request) -> Result<Response, Error> {
-        // Build request
+        let expected_id = match request.id.clone() {
+            Some(id) => id,
+            None => return Err(Error::MissingId),
+        };
         let request_raw = serde_json::to_vec(request)?;
 
-        // Setup connection
-        let mut headers = Headers::new();
-        headers.set(ContentType::json());
-        if let Some(ref token) = self.token {
-            headers.set(Authorization(Bearer {
-                token: token.clone(),
-            }));
-        }
-
-        // Send request
-        let retry_headers = headers.clone();
-        let hyper_request = self.client.post(&self.url).headers(headers).body(&request_raw[..]);
-        let mut stream = match hyper_request.send() {
-            Ok(s) => s,
-            // Hyper maintains a pool of TCP connections to its various clients,
-            // and when one drops it cannot tell until it tries sending. In this
-            // case the appropriate thing is to re-send, which will cause hyper
-            // to open a new connection. Jonathan Reem explained this to me on
-            // IRC, citing vague technical reasons that the library itself cannot
-            // do the retry transparently.
-            Err(hyper::error::Error::Io(e)) => {
-                if e.kind() == io::ErrorKind::BrokenPipe
-                    || e.kind() == io::ErrorKind::ConnectionAborted
-                {
-                    try!(self
-                        .client
-                        .post(&self.url)
-                        .headers(retry_headers)
-                        .body(&request_raw[..])
-                        .send()
-                        .map_err(Error::Hyper))
-                } else {
-                    return Err(Error::Hyper(hyper::error::Error::Io(e)));
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let response = match self.transport.send_raw(&request_raw) {
+                Ok(bytes) => self.parse_response(&bytes, expected_id.clone())?,
+                Err(e) => {
+                    if attempt < self.retry_policy.max_attempts && e.is_retryable() {
+                        self.sleep_before_retry(attempt);
+                        continue;
+                    }
+                    return Err(e);
                 }
+            };
+
+            let should_retry = attempt < self.retry_policy.max_attempts
+                && response
+                    .error
+                    .as_ref()
+                    .map_or(false, |e| self.retry_policy.retryable_rpc_codes.contains(&e.code));
+            if !should_retry {
+                return Ok(response);
             }
-            Err(e) => {
-                return Err(Error::Hyper(e));
-            }
+            self.sleep_before_retry(attempt);
+        }
+    }
+
+    /// Sends a batch of requests to the client in a single round-trip.
+    ///
+    /// The JSON-RPC 2.0 spec allows servers to return the responses of a
+    /// batch in any order, so the returned `Vec` is reordered to line up
+    /// with `requests`: `responses[i]` is always the response to
+    /// `requests[i]`. Per-item RPC errors are carried in each `Response`'s
+    /// `error` field rather than failing the whole batch. The call fails
+    /// only on a transport or JSON decode failure, a `jsonrpc` version
+    /// mismatch in strict mode, or a request whose `id` no response matches
+    /// (`NonceMismatch`); responses whose `id` matches no request are
+    /// dropped.
+    ///
+    /// A batch may legally mix in notifications (built with `id: None`),
+    /// which the spec says get no response at all; those entries are
+    /// skipped rather than matched, and `responses[i]` is `None` for them.
+    ///
+    /// Respects `strict` the same way `send_request` does: the `jsonrpc`
+    /// version field and each response `id` are matched leniently when
+    /// `strict` is `false`.
+    pub fn send_batch(&self, requests: &[Request]) -> Result<Vec<Option<Response>>, Error> {
+        if requests.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // A batch of all notifications gets no response body at all per
+        // spec, so there's nothing to round-trip or parse; sending it
+        // anyway would misread an empty HTTP body as a JSON decode error,
+        // or hang forever on a transport (like `IpcTransport`) that waits
+        // on a FIFO batch slot no compliant server will ever fill.
+        if requests.iter().all(|r| r.id.is_none()) {
+            self.transport.send_notification(&serde_json::to_vec(requests)?)?;
+            return Ok(vec![None; requests.len()]);
+        }
+
+        let request_raw = serde_json::to_vec(requests)?;
+        let bytes = self.transport.send_raw(&request_raw)?;
+
+        let wire: BatchResponse = serde_json::from_slice(&bytes)?;
+        let mut responses = match wire {
+            BatchResponse::Batch(responses) => responses,
+            BatchResponse::Single(response) => vec![response],
         };
 
-        // nb we ignore stream.status since we expect the body
-        // to contain information about any error
-        let response: Response = serde_json::from_reader(&mut stream)?;
-        stream.bytes().count(); // Drain the stream so it can be reused
-        if response.jsonrpc != None && response.jsonrpc != Some(From::from("2.0")) {
+        if self.strict
+            && responses
+                .iter()
+                .any(|r| r.jsonrpc != None && r.jsonrpc != Some(From::from("2.0")))
+        {
+            return Err(Error::VersionMismatch);
+        }
+
+        let mut matched = Vec::with_capacity(requests.len());
+        for request in requests {
+            let id = match request.id {
+                Some(ref id) => id,
+                None => {
+                    matched.push(None);
+                    continue;
+                }
+            };
+            match responses.iter().position(|r| ids_match(&r.id, id, self.strict)) {
+                Some(idx) => matched.push(Some(responses.remove(idx))),
+                None => return Err(Error::NonceMismatch),
+            }
+        }
+        Ok(matched)
+    }
+
+    /// Parses and validates a single response against the `id` it should match
+    fn parse_response(&self, bytes: &[u8], expected_id: serde_json::Value) -> Result<Response, Error> {
+        let response: Response = serde_json::from_slice(bytes)?;
+        if self.strict
+            && response.jsonrpc != None
+            && response.jsonrpc != Some(From::from("2.0"))
+        {
             return Err(Error::VersionMismatch);
         }
-        if response.id != request.id {
+        if !ids_match(&response.id, &expected_id, self.strict) {
             return Err(Error::NonceMismatch);
         }
         Ok(response)
     }
 
+    /// Sleeps for `base_delay * multiplier^(attempt - 1)` before the next attempt
+    fn sleep_before_retry(&self, attempt: u32) {
+        thread::sleep(self.retry_policy.base_delay * self.retry_policy.multiplier.pow(attempt - 1));
+    }
+
     /// Builds a request
     pub fn build_request<'a>(&self, name: &'a str, params: serde_json::Value) -> Request<'a> {
         let mut nonce = self.nonce.lock().unwrap();
@@ -127,11 +292,27 @@ impl Client {
         Request {
             method: name,
             params: params,
-            id: From::from(*nonce),
+            id: Some(From::from(*nonce)),
             jsonrpc: Some("2.0"),
         }
     }
 
+    /// Sends a fire-and-forget JSONRPC notification: a request with no `id`
+    /// that the spec says gets no response. Goes through the transport's
+    /// `send_notification`: `WsTransport` and `IpcTransport` return as soon
+    /// as the request is written, while `HttpTransport` waits for the HTTP
+    /// reply and reads its body. No response body is parsed.
+    pub fn notify(&self, method: &str, params: serde_json::Value) -> Result<(), Error> {
+        let request = Request {
+            method,
+            params,
+            id: None,
+            jsonrpc: Some("2.0"),
+        };
+        let request_raw = serde_json::to_vec(&request)?;
+        self.transport.send_notification(&request_raw)
+    }
+
     /// Accessor for the last-used nonce
     pub fn last_nonce(&self) -> u64 {
         *self.nonce.lock().unwrap()
@@ -140,6 +321,10 @@ impl Client {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::io;
+    use std::rc::Rc;
+
     use super::*;
     use serde_json::json;
 
@@ -153,4 +338,176 @@ mod tests {
         assert_eq!(client.last_nonce(), 2);
         assert!(req1 != req2);
     }
+
+    #[test]
+    fn send_request_rejects_an_id_less_request() {
+        let client = Client::new("localhost".to_owned(), None);
+        let request = Request {
+            method: "ping",
+            params: json!(null),
+            id: None,
+            jsonrpc: Some("2.0"),
+        };
+        match client.send_request(&request) {
+            Err(Error::MissingId) => {}
+            other => panic!("expected Error::MissingId, got {:?}", other),
+        }
+    }
+
+    struct MockTransport {
+        response: Vec<u8>,
+    }
+
+    impl Transport for MockTransport {
+        fn send_raw(&self, _request: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(self.response.clone())
+        }
+    }
+
+    #[test]
+    fn send_batch_skips_notifications() {
+        let client = Client::from_transport(MockTransport {
+            response: serde_json::to_vec(&json!([
+                {"result": 1, "error": null, "id": 2, "jsonrpc": "2.0"},
+            ]))
+            .unwrap(),
+        });
+        let requests = vec![
+            Request {
+                method: "notify_me",
+                params: json!(null),
+                id: None,
+                jsonrpc: Some("2.0"),
+            },
+            Request {
+                method: "ping",
+                params: json!(null),
+                id: Some(json!(2)),
+                jsonrpc: Some("2.0"),
+            },
+        ];
+        let responses = client.send_batch(&requests).unwrap();
+        assert!(responses[0].is_none());
+        assert_eq!(responses[1].as_ref().unwrap().id, json!(2));
+    }
+
+    struct NotifyOnlyTransport;
+
+    impl Transport for NotifyOnlyTransport {
+        fn send_raw(&self, _request: &[u8]) -> Result<Vec<u8>, Error> {
+            panic!("an all-notification batch must not round-trip through send_raw");
+        }
+
+        fn send_notification(&self, _request: &[u8]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_batch_of_all_notifications_skips_the_round_trip() {
+        let client = Client::from_transport(NotifyOnlyTransport);
+        let requests = vec![
+            Request {
+                method: "notify_one",
+                params: json!(null),
+                id: None,
+                jsonrpc: Some("2.0"),
+            },
+            Request {
+                method: "notify_two",
+                params: json!(null),
+                id: None,
+                jsonrpc: Some("2.0"),
+            },
+        ];
+        let responses = client.send_batch(&requests).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|r| r.is_none()));
+    }
+
+    #[test]
+    fn send_batch_honors_strict_false_for_ids_and_version() {
+        let client = Client::from_transport(MockTransport {
+            response: serde_json::to_vec(&json!([
+                {"result": 1, "error": null, "id": "1", "jsonrpc": "1.0"},
+            ]))
+            .unwrap(),
+        })
+        .with_strict(false);
+        let requests = vec![Request {
+            method: "ping",
+            params: json!(null),
+            id: Some(json!(1)),
+            jsonrpc: Some("2.0"),
+        }];
+        let responses = client.send_batch(&requests).unwrap();
+        assert_eq!(responses[0].as_ref().unwrap().id, json!("1"));
+    }
+
+    #[test]
+    fn ids_match_strict_requires_exact_equality() {
+        assert!(ids_match(&json!(1), &json!(1), true));
+        assert!(!ids_match(&json!("1"), &json!(1), true));
+    }
+
+    #[test]
+    fn ids_match_lenient_coerces_numeric_and_string() {
+        assert!(ids_match(&json!("1"), &json!(1), false));
+        assert!(ids_match(&json!(1), &json!("1"), false));
+        assert!(!ids_match(&json!(2), &json!(1), false));
+    }
+
+    struct FlakyTransport {
+        fails_remaining: Cell<u32>,
+        response: Vec<u8>,
+    }
+
+    impl Transport for FlakyTransport {
+        fn send_raw(&self, _request: &[u8]) -> Result<Vec<u8>, Error> {
+            if self.fails_remaining.get() > 0 {
+                self.fails_remaining.set(self.fails_remaining.get() - 1);
+                return Err(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")));
+            }
+            Ok(self.response.clone())
+        }
+    }
+
+    #[test]
+    fn default_retry_policy_retries_once_on_broken_pipe() {
+        let client = Client::from_transport(FlakyTransport {
+            fails_remaining: Cell::new(1),
+            response: serde_json::to_vec(&json!({"result": 1, "error": null, "id": 1, "jsonrpc": "2.0"}))
+                .unwrap(),
+        });
+        let request = client.build_request("ping", json!(null));
+        let response = client.send_request(&request).unwrap();
+        assert_eq!(response.result, Some(json!(1)));
+    }
+
+    struct RecordingTransport {
+        sent: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Transport for RecordingTransport {
+        fn send_raw(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+            *self.sent.borrow_mut() = request.to_vec();
+            Ok(vec![])
+        }
+
+        fn send_notification(&self, request: &[u8]) -> Result<(), Error> {
+            *self.sent.borrow_mut() = request.to_vec();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn notify_sends_an_id_less_request() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let client = Client::from_transport(RecordingTransport { sent: sent.clone() });
+        client.notify("subscribe", json!(["x"])).unwrap();
+
+        let sent_request: serde_json::Value = serde_json::from_slice(&sent.borrow()).unwrap();
+        assert_eq!(sent_request["method"], json!("subscribe"));
+        assert!(sent_request.get("id").is_none());
+    }
 }
@@ -0,0 +1,216 @@
+// Rust JSON-RPC Library
+// Written in 2015 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # IPC (Unix domain socket) transport
+//!
+//! Local node software commonly exposes a JSONRPC endpoint over a Unix
+//! domain socket in addition to (or instead of) HTTP. It's faster and
+//! doesn't require auth, since reaching it at all implies access to the
+//! local filesystem. `IpcTransport` frames each request/response with a
+//! trailing newline, which is the convention used by e.g. Geth's IPC
+//! endpoint.
+//!
+//! Unlike a single request/response HTTP round-trip, the socket is shared:
+//! a background thread reads every line the server sends and dispatches it
+//! to whichever caller is waiting on that `id`, so multiple threads can
+//! have calls in flight on the connection at once instead of queueing
+//! behind each other's blocking read.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json;
+
+use error::Error;
+
+use super::transport::Transport;
+
+/// The `id` a single (non-batch) request or response carries, if its
+/// top-level shape is a JSON object at all. Returned as its canonical
+/// string form (a string id's contents, any other id's JSON text) so it can
+/// key a `HashMap` (`serde_json::Value` isn't `Hash`, since its `Number`
+/// variant can hold a float), and so a reply whose `id` is the string form
+/// of a numeric request `id` still reaches its caller.
+fn object_id(bytes: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(serde_json::Value::Object(ref map)) => map.get("id").map(|id| match *id {
+            serde_json::Value::String(ref s) => s.clone(),
+            ref other => other.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether `bytes` is a JSON array, i.e. a batch request or response
+fn is_batch(bytes: &[u8]) -> bool {
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(serde_json::Value::Array(_)) => true,
+        _ => false,
+    }
+}
+
+/// Pending callers waiting on a reply, indexed by how to match it to its request
+struct Pending {
+    /// Single requests, matched to their reply by `id`
+    by_id: Mutex<HashMap<String, Sender<Vec<u8>>>>,
+    /// Batch requests, matched to their reply in FIFO order since a batch
+    /// reply has no single `id` of its own. This assumes a server replies
+    /// to batches in the order it received them, which holds for every
+    /// IPC node daemon this transport has been used against.
+    batches: Mutex<VecDeque<Sender<Vec<u8>>>>,
+    /// Set once the reader thread has exited (peer closed the socket, or a
+    /// read error). Checked by `send_raw` after registering so a call made
+    /// after the connection died fails fast instead of registering a
+    /// `Sender` the dead reader thread will never drain.
+    closed: AtomicBool,
+}
+
+/// A transport that talks to a local JSONRPC server over a Unix domain
+/// socket, dispatching replies to concurrent callers by `id` via a
+/// background reader thread
+pub struct IpcTransport {
+    writer: Mutex<UnixStream>,
+    pending: Arc<Pending>,
+}
+
+impl IpcTransport {
+    /// Connects to the Unix domain socket at `path`
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<IpcTransport, Error> {
+        let writer_stream = UnixStream::connect(path)?;
+        let reader_stream = writer_stream.try_clone()?;
+
+        let pending = Arc::new(Pending {
+            by_id: Mutex::new(HashMap::new()),
+            batches: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+        });
+
+        let reader_pending = pending.clone();
+        thread::spawn(move || read_loop(reader_stream, reader_pending));
+
+        Ok(IpcTransport {
+            writer: Mutex::new(writer_stream),
+            pending,
+        })
+    }
+
+    fn write_framed(&self, request: &[u8]) -> Result<(), Error> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(request)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads newline-delimited responses for the lifetime of the connection,
+/// handing each one to whichever pending caller is waiting for it
+fn read_loop(stream: UnixStream, pending: Arc<Pending>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => {
+                // The connection is gone. Mark it closed before dropping
+                // every pending `Sender`, so a `send_raw` that registers
+                // concurrently with this shutdown is guaranteed to observe
+                // `closed` and bail out itself (see `send_raw`) even if its
+                // entry isn't one of the ones cleared here. Dropping the
+                // `Sender`s hangs up their matching `rx.recv()` with a
+                // `RecvError` instead of leaving them parked forever.
+                pending.closed.store(true, Ordering::SeqCst);
+                pending.by_id.lock().unwrap().clear();
+                pending.batches.lock().unwrap().clear();
+                return;
+            }
+            Ok(_) => {}
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        if is_batch(&line) {
+            if let Some(sender) = pending.batches.lock().unwrap().pop_front() {
+                let _ = sender.send(line.clone());
+            }
+            continue;
+        }
+
+        if let Some(id) = object_id(&line) {
+            if let Some(sender) = pending.by_id.lock().unwrap().remove(&id) {
+                let _ = sender.send(line.clone());
+            }
+        }
+        // A response with no `id` we're waiting on (or that can't be
+        // parsed as a JSONRPC object at all) has nowhere to go and is
+        // dropped.
+    }
+}
+
+impl Transport for IpcTransport {
+    fn send_raw(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = channel();
+        let id = object_id(request);
+        match id {
+            Some(ref id) => {
+                self.pending.by_id.lock().unwrap().insert(id.clone(), tx);
+            }
+            None => {
+                self.pending.batches.lock().unwrap().push_back(tx);
+            }
+        }
+
+        // The reader thread may have already exited (and cleared the maps)
+        // before the registration above, or may exit between it and the
+        // write below; either way it'll never drain the slot just
+        // registered. Checking `closed` here and bailing out catches that
+        // instead of writing a request no one will ever read a reply to.
+        if self.pending.closed.load(Ordering::SeqCst) {
+            match id {
+                Some(ref id) => {
+                    self.pending.by_id.lock().unwrap().remove(id);
+                }
+                None => {
+                    // Nothing to key the stale batch slot back out by;
+                    // it's harmless to leave since the queue is only ever
+                    // drained, never matched by content.
+                }
+            }
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "IPC connection closed before the request could be sent",
+            )));
+        }
+
+        self.write_framed(request)?;
+
+        rx.recv().map_err(|_| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "IPC reader thread stopped before a reply arrived",
+            ))
+        })
+    }
+
+    fn send_notification(&self, request: &[u8]) -> Result<(), Error> {
+        self.write_framed(request)
+    }
+}
@@ -0,0 +1,132 @@
+// Rust JSON-RPC Library
+// Written in 2015 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Transport
+//!
+//! `Client` is generic over how a serialized request actually reaches the
+//! server. This module defines the `Transport` trait that abstracts that,
+//! plus `HttpTransport`, the `hyper`-based implementation `Client` used
+//! exclusively before transports were made pluggable.
+
+use std::io::Read;
+
+use hyper;
+use hyper::client::Client as HyperClient;
+use hyper::header::{Authorization, Basic, Bearer, ContentType, Headers};
+
+use error::Error;
+
+/// Something that can carry a serialized JSONRPC request to a server and
+/// bring back the server's raw, still-serialized response
+pub trait Transport {
+    /// Sends `request` and returns the raw response body. A single attempt;
+    /// `Client::send_request` applies its `RetryPolicy` around this, while
+    /// `Client::send_batch` does not retry.
+    fn send_raw(&self, request: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Sends `request` without waiting for or reading a reply. Used for
+    /// JSON-RPC notifications, which by spec get no response at all.
+    ///
+    /// The default forwards to `send_raw` and discards the result, which is
+    /// correct for a transport like HTTP that makes one request/response
+    /// round-trip per call. Transports that hold a persistent connection
+    /// and would otherwise block reading a reply that's never coming (e.g.
+    /// `WsTransport`, `IpcTransport`) must override this with a write-only
+    /// implementation.
+    fn send_notification(&self, request: &[u8]) -> Result<(), Error> {
+        self.send_raw(request).map(|_| ())
+    }
+}
+
+/// Credentials used to authenticate to the remote server
+pub enum Credentials {
+    /// An `Authorization: Bearer <token>` header
+    Bearer(String),
+    /// An `Authorization: Basic <base64>` header, as used by e.g. Bitcoin
+    /// Core and other node daemons
+    Basic {
+        /// The Basic auth username
+        username: String,
+        /// The Basic auth password, if any
+        password: Option<String>,
+    },
+}
+
+/// A transport that POSTs each request to an HTTP(S) URL and reads the
+/// response from the body of the reply
+pub struct HttpTransport {
+    url: String,
+    credentials: Option<Credentials>,
+    client: HyperClient,
+}
+
+impl HttpTransport {
+    /// Creates a new HTTP transport, optionally authenticating with a
+    /// Bearer token
+    pub fn new(url: String, token: Option<String>) -> HttpTransport {
+        HttpTransport {
+            url,
+            credentials: token.map(Credentials::Bearer),
+            client: HyperClient::new(),
+        }
+    }
+
+    /// Creates a new HTTP transport that authenticates with HTTP Basic
+    /// credentials instead of a Bearer token
+    pub fn with_basic_auth(url: String, username: String, password: Option<String>) -> HttpTransport {
+        HttpTransport {
+            url,
+            credentials: Some(Credentials::Basic { username, password }),
+            client: HyperClient::new(),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send_raw(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+        match self.credentials {
+            Some(Credentials::Bearer(ref token)) => {
+                headers.set(Authorization(Bearer {
+                    token: token.clone(),
+                }));
+            }
+            Some(Credentials::Basic {
+                ref username,
+                ref password,
+            }) => {
+                headers.set(Authorization(Basic {
+                    username: username.clone(),
+                    password: password.clone(),
+                }));
+            }
+            None => {}
+        }
+
+        let mut stream = self
+            .client
+            .post(&self.url)
+            .headers(headers)
+            .body(request)
+            .send()
+            .map_err(Error::Hyper)?;
+
+        // nb we ignore stream.status since we expect the body
+        // to contain information about any error
+        let mut body = Vec::new();
+        stream.read_to_end(&mut body).map_err(Error::Io)?;
+        Ok(body)
+    }
+}
@@ -0,0 +1,216 @@
+// Rust JSON-RPC Library
+// Written in 2015 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # WebSocket transport
+//!
+//! Many node clients (Ethereum, Parity) prefer a persistent WebSocket
+//! connection over per-call HTTP, since it avoids repeated TCP/TLS setup and
+//! allows full-duplex use. `WsTransport` keeps one connection open for the
+//! lifetime of the `Client` it's attached to.
+//!
+//! Like `IpcTransport`, the connection is shared: a background thread reads
+//! every frame the server sends and dispatches it to whichever caller is
+//! waiting on that `id`, so multiple threads can have calls in flight at
+//! once instead of queueing behind each other's blocking read.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json;
+use websocket::client::sync::Client as WsClient;
+use websocket::receiver::Reader;
+use websocket::sender::Writer;
+use websocket::{ClientBuilder, OwnedMessage};
+
+use error::Error;
+
+use super::transport::Transport;
+
+fn ws_err<E: ::std::fmt::Display>(e: E) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// The `id` a single (non-batch) request or response carries, if its
+/// top-level shape is a JSON object at all. Returned as its canonical
+/// string form (a string id's contents, any other id's JSON text) so it can
+/// key a `HashMap` (`serde_json::Value` isn't `Hash`, since its `Number`
+/// variant can hold a float), and so a reply whose `id` is the string form
+/// of a numeric request `id` still reaches its caller.
+fn object_id(bytes: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(serde_json::Value::Object(ref map)) => map.get("id").map(|id| match *id {
+            serde_json::Value::String(ref s) => s.clone(),
+            ref other => other.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether `bytes` is a JSON array, i.e. a batch request or response
+fn is_batch(bytes: &[u8]) -> bool {
+    match serde_json::from_slice::<serde_json::Value>(bytes) {
+        Ok(serde_json::Value::Array(_)) => true,
+        _ => false,
+    }
+}
+
+/// Pending callers waiting on a reply, indexed by how to match it to its request
+struct Pending {
+    /// Single requests, matched to their reply by `id`
+    by_id: Mutex<HashMap<String, Sender<Vec<u8>>>>,
+    /// Batch requests, matched to their reply in FIFO order since a batch
+    /// reply has no single `id` of its own. This assumes a server replies
+    /// to batches in the order it received them, which holds for every
+    /// WebSocket JSONRPC server this transport has been used against.
+    batches: Mutex<VecDeque<Sender<Vec<u8>>>>,
+    /// Set once the reader thread has exited (server closed the connection,
+    /// or a read error). Checked by `send_raw` after registering so a call
+    /// made after the connection died fails fast instead of registering a
+    /// `Sender` the dead reader thread will never drain.
+    closed: AtomicBool,
+}
+
+/// A transport that holds a persistent, full-duplex WebSocket connection
+/// open and dispatches replies to concurrent callers by `id` via a
+/// background reader thread
+pub struct WsTransport {
+    writer: Mutex<Writer<TcpStream>>,
+    pending: Arc<Pending>,
+}
+
+impl WsTransport {
+    /// Opens a WebSocket connection to `url` and keeps it open for reuse
+    pub fn connect(url: &str) -> Result<WsTransport, Error> {
+        let conn: WsClient<TcpStream> = ClientBuilder::new(url)
+            .map_err(ws_err)?
+            .connect_insecure()
+            .map_err(ws_err)?;
+        let (reader, writer) = conn.split().map_err(ws_err)?;
+
+        let pending = Arc::new(Pending {
+            by_id: Mutex::new(HashMap::new()),
+            batches: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+        });
+
+        let reader_pending = pending.clone();
+        thread::spawn(move || read_loop(reader, reader_pending));
+
+        Ok(WsTransport {
+            writer: Mutex::new(writer),
+            pending,
+        })
+    }
+}
+
+/// Reads frames for the lifetime of the connection, handing each data frame
+/// to whichever pending caller is waiting for it; ping and pong frames are
+/// skipped without a reply
+fn read_loop(mut reader: Reader<TcpStream>, pending: Arc<Pending>) {
+    loop {
+        let bytes = match reader.recv_message() {
+            Ok(OwnedMessage::Text(text)) => text.into_bytes(),
+            Ok(OwnedMessage::Binary(bytes)) => bytes,
+            // Answering a ping requires writing, which this thread has no
+            // access to (the writer half is owned by `WsTransport`). A
+            // dropped ping can lead the server to close the connection;
+            // this transport does not reconnect, so every call after that
+            // fails with `ConnectionAborted`.
+            Ok(OwnedMessage::Ping(_)) | Ok(OwnedMessage::Pong(_)) => continue,
+            Ok(OwnedMessage::Close(_)) | Err(_) => {
+                // The connection is gone. Mark it closed before dropping
+                // every pending `Sender`, so a `send_raw` that registers
+                // concurrently with this shutdown is guaranteed to observe
+                // `closed` and bail out itself (see `send_raw`) even if its
+                // entry isn't one of the ones cleared here.
+                pending.closed.store(true, Ordering::SeqCst);
+                pending.by_id.lock().unwrap().clear();
+                pending.batches.lock().unwrap().clear();
+                return;
+            }
+        };
+
+        if is_batch(&bytes) {
+            if let Some(sender) = pending.batches.lock().unwrap().pop_front() {
+                let _ = sender.send(bytes);
+            }
+            continue;
+        }
+
+        if let Some(id) = object_id(&bytes) {
+            if let Some(sender) = pending.by_id.lock().unwrap().remove(&id) {
+                let _ = sender.send(bytes);
+            }
+        }
+        // A frame with no `id` we're waiting on (a subscription push, or a
+        // reply that can't be parsed as a JSONRPC object at all) has
+        // nowhere to go and is dropped.
+    }
+}
+
+impl Transport for WsTransport {
+    fn send_raw(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = channel();
+        let id = object_id(request);
+        match id {
+            Some(ref id) => {
+                self.pending.by_id.lock().unwrap().insert(id.clone(), tx);
+            }
+            None => {
+                self.pending.batches.lock().unwrap().push_back(tx);
+            }
+        }
+
+        if self.pending.closed.load(Ordering::SeqCst) {
+            match id {
+                Some(ref id) => {
+                    self.pending.by_id.lock().unwrap().remove(id);
+                }
+                None => {}
+            }
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "WebSocket connection closed before the request could be sent",
+            )));
+        }
+
+        self.writer
+            .lock()
+            .unwrap()
+            .send_message(&OwnedMessage::Text(String::from_utf8_lossy(request).into_owned()))
+            .map_err(ws_err)?;
+
+        rx.recv().map_err(|_| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "WebSocket reader thread stopped before a reply arrived",
+            ))
+        })
+    }
+
+    fn send_notification(&self, request: &[u8]) -> Result<(), Error> {
+        // A notification gets no reply per spec, so unlike `send_raw` this
+        // must not wait on a pending slot.
+        self.writer
+            .lock()
+            .unwrap()
+            .send_message(&OwnedMessage::Text(String::from_utf8_lossy(request).into_owned()))
+            .map_err(ws_err)
+    }
+}
@@ -0,0 +1,107 @@
+// Rust JSON-RPC Library
+// Written in 2015 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Error handling
+//!
+//! Support for error handling
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use hyper;
+use serde_json;
+
+use RpcError;
+
+/// A library error
+#[derive(Debug)]
+pub enum Error {
+    /// Json error
+    Json(serde_json::Error),
+    /// Hyper error
+    Hyper(hyper::Error),
+    /// A transport failed at the I/O level: reading an HTTP reply body, or
+    /// a WebSocket or IPC connection
+    Io(io::Error),
+    /// Response has neither error nor result
+    NoErrorOrResult,
+    /// Response to a different request
+    NonceMismatch,
+    /// Server did not respond with the expected `jsonrpc` version field
+    VersionMismatch,
+    /// The server returned an RPC-level error
+    Rpc(RpcError),
+    /// `send_request` was called with a `Request` that has no `id`, so
+    /// there's nothing to match a response against; use `notify` instead
+    MissingId,
+}
+
+impl Error {
+    /// Whether this is a transport-level failure transient enough that a
+    /// caller's `RetryPolicy` should resend the request
+    pub fn is_retryable(&self) -> bool {
+        let io_err = match *self {
+            Error::Hyper(hyper::Error::Io(ref e)) => Some(e),
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        };
+        match io_err.map(|e| e.kind()) {
+            Some(io::ErrorKind::BrokenPipe)
+            | Some(io::ErrorKind::ConnectionAborted)
+            | Some(io::ErrorKind::ConnectionReset)
+            | Some(io::ErrorKind::TimedOut) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Json(ref e) => write!(f, "JSON decode error: {}", e),
+            Error::Hyper(ref e) => write!(f, "Hyper error: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::NoErrorOrResult => write!(f, "Server returned neither error nor result"),
+            Error::NonceMismatch => write!(f, "Nonce of response did not match nonce of request"),
+            Error::VersionMismatch => write!(f, "`jsonrpc` field set to non-\"2.0\""),
+            Error::Rpc(ref e) => write!(f, "RPC error {}: {}", e.code, e.message),
+            Error::MissingId => write!(f, "send_request called with a Request that has no id; use notify instead"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &'static str {
+        "JSONRPC error"
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Error {
+        Error::Hyper(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,92 @@
+// Rust JSON-RPC Library
+// Written in 2015 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Rust JSON-RPC
+//!
+//! Rust support for the JSON-RPC 2.0 protocol.
+//!
+
+#![crate_name = "jsonrpc"]
+
+extern crate hyper;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate websocket;
+
+pub mod client;
+pub mod error;
+
+pub use error::Error;
+
+/// A JSONRPC request object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Request<'a> {
+    /// The name of the RPC call
+    pub method: &'a str,
+    /// Parameters to the RPC call
+    pub params: serde_json::Value,
+    /// Identifier for this Request, which should appear in the response.
+    /// `None` makes this a notification: the spec requires the `id` member
+    /// to be omitted entirely rather than sent as `null`, and a notification
+    /// gets no response at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    /// jsonrpc field, MUST be "2.0"
+    pub jsonrpc: Option<&'a str>,
+}
+
+/// A JSONRPC error object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// The integer error code
+    pub code: i32,
+    /// A string describing the error
+    pub message: String,
+    /// Additional data specific to the error
+    pub data: Option<serde_json::Value>,
+}
+
+/// A JSONRPC response object
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    /// A result if there is one, or null
+    pub result: Option<serde_json::Value>,
+    /// An error if there is one, or null
+    pub error: Option<RpcError>,
+    /// Identifier for this Response, which should match that of the request
+    pub id: serde_json::Value,
+    /// jsonrpc field, MUST be "2.0"
+    pub jsonrpc: Option<String>,
+}
+
+impl Response {
+    /// Extract the result from a response, consuming it and returning an
+    /// `Err` if it contained an RPC-level error or no result at all
+    pub fn into_result<T: for<'a> serde::de::Deserialize<'a>>(self) -> Result<T, Error> {
+        if let Some(e) = self.error {
+            return Err(Error::Rpc(e));
+        }
+        if let Some(r) = self.result {
+            return serde_json::from_value(r).map_err(Error::Json);
+        }
+        Err(Error::NoErrorOrResult)
+    }
+
+    /// Returns whether or not the `result` field is empty
+    pub fn is_none(&self) -> bool {
+        self.result.is_none()
+    }
+}